@@ -0,0 +1,475 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fs,
+    hash::{Hash, Hasher},
+    mem,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    task,
+    time::{Duration, Instant},
+};
+
+use bytes::{Buf, Bytes};
+use futures::future::BoxFuture;
+use headers::{CacheControl, HeaderMapExt};
+use http::{
+    header::{HeaderName, AGE},
+    HeaderMap, HeaderValue, Method, Request, Response, StatusCode,
+};
+use http_body::Body;
+use http_body_util::{combinators::BoxBody, BodyExt, Full};
+use moka::sync::Cache;
+use tower_layer::Layer;
+use tower_service::Service;
+
+/// Key identifying a cached response.
+///
+/// Holds the canonical method + URI + selected `Vary` header bytes verbatim, so
+/// lookups compare on the real key rather than a digest — a hash collision can
+/// only produce a miss, never a wrong-content hit.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Key(String);
+impl Key {
+    fn from_request<B>(req: &Request<B>, vary: &[HeaderName]) -> Self {
+        let mut canonical = format!("{} {}", req.method(), req.uri());
+        for name in vary {
+            for value in req.headers().get_all(name) {
+                canonical.push('\n');
+                canonical.push_str(name.as_str());
+                canonical.push(':');
+                // One char per byte, so distinct values never share a key.
+                canonical.extend(value.as_bytes().iter().map(|&b| char::from(b)));
+            }
+        }
+        Self(canonical)
+    }
+    /// A short digest for use as a file name; never used for equality.
+    fn digest(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.0.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+/// A buffered response held in a [CacheStore].
+#[derive(Clone, Debug)]
+pub struct StoredResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    /// When the entry entered the cache, as Unix-epoch nanoseconds, used to
+    /// stamp the `Age` header on a hit.
+    stored_at: u128,
+}
+impl StoredResponse {
+    fn into_response<E>(self) -> Response<BoxBody<Bytes, E>> {
+        let mut response = Response::new(buffered(self.body));
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.headers;
+        let age = now_nanos().saturating_sub(self.stored_at) / 1_000_000_000;
+        if let Ok(value) = HeaderValue::from_str(&age.to_string()) {
+            response.headers_mut().insert(AGE, value);
+        }
+        response
+    }
+}
+
+/// Storage backend for [ResponseCacheService].
+///
+/// Implementations own their own synchronisation, so the service only needs a
+/// shared reference. The `ttl` handed to [put](CacheStore::put) is the lifetime
+/// [CacheControlService](crate::CacheControlService) derived from the response,
+/// letting the two layers compose: one decides cacheability, the other persists
+/// it.
+pub trait CacheStore: Clone + Send + Sync + 'static {
+    fn get(&self, key: &Key) -> Option<StoredResponse>;
+    fn put(&self, key: Key, response: StoredResponse, ttl: Duration);
+}
+
+/// In-memory [CacheStore] backed by a bounded [moka](moka::sync::Cache).
+///
+/// The cache enforces the capacity bound; the per-entry *TTL* is honoured
+/// lazily on read, since it varies per response rather than being fixed for the
+/// whole cache.
+#[derive(Clone)]
+pub struct MokaStore {
+    inner: Cache<Key, (StoredResponse, Instant)>,
+}
+impl MokaStore {
+    pub fn new(capacity: u64) -> Self {
+        Self {
+            inner: Cache::new(capacity),
+        }
+    }
+}
+impl Default for MokaStore {
+    fn default() -> Self {
+        Self::new(1_024)
+    }
+}
+impl CacheStore for MokaStore {
+    fn get(&self, key: &Key) -> Option<StoredResponse> {
+        match self.inner.get(key) {
+            Some((_, expiry)) if expiry <= Instant::now() => {
+                self.inner.invalidate(key);
+                None
+            }
+            Some((response, _)) => Some(response),
+            None => None,
+        }
+    }
+    fn put(&self, key: Key, response: StoredResponse, ttl: Duration) {
+        self.inner.insert(key, (response, Instant::now() + ttl));
+    }
+}
+
+/// In-memory [CacheStore] backed by a plain [HashMap], expiring entries lazily.
+#[derive(Clone, Default)]
+pub struct HashMapStore {
+    inner: Arc<Mutex<HashMap<Key, (StoredResponse, Instant)>>>,
+}
+impl HashMapStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+impl CacheStore for HashMapStore {
+    fn get(&self, key: &Key) -> Option<StoredResponse> {
+        let mut map = self.inner.lock().expect("cache mutex poisoned");
+        match map.get(key) {
+            Some((_, expiry)) if *expiry <= Instant::now() => {
+                map.remove(key);
+                None
+            }
+            Some((response, _)) => Some(response.clone()),
+            None => None,
+        }
+    }
+    fn put(&self, key: Key, response: StoredResponse, ttl: Duration) {
+        let mut map = self.inner.lock().expect("cache mutex poisoned");
+        map.insert(key, (response, Instant::now() + ttl));
+    }
+}
+
+/// On-disk [CacheStore] persisting each entry as a file under a root directory.
+#[derive(Clone)]
+pub struct DiskStore {
+    root: PathBuf,
+}
+impl DiskStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+    fn path(&self, key: &Key) -> PathBuf {
+        self.root.join(format!("{:016x}", key.digest()))
+    }
+}
+impl CacheStore for DiskStore {
+    fn get(&self, key: &Key) -> Option<StoredResponse> {
+        let bytes = fs::read(self.path(key)).ok()?;
+        let (expiry_nanos, rest) = bytes.split_first_chunk::<16>()?;
+        let expiry = u128::from_le_bytes(*expiry_nanos);
+        if expiry <= now_nanos() {
+            let _ = fs::remove_file(self.path(key));
+            return None;
+        }
+        let (stored_key, response) = decode(rest)?;
+        // Guard against a digest collision handing back another URI's body.
+        if stored_key != key.0 {
+            return None;
+        }
+        Some(response)
+    }
+    fn put(&self, key: Key, response: StoredResponse, ttl: Duration) {
+        if fs::create_dir_all(&self.root).is_err() {
+            return;
+        }
+        let mut buf = (now_nanos() + ttl.as_nanos()).to_le_bytes().to_vec();
+        encode(&key, &response, &mut buf);
+        let _ = fs::write(self.path(&key), buf);
+    }
+}
+
+/// Middleware [Layer] for the [ResponseCacheService] service.
+#[derive(Clone, Debug)]
+pub struct ResponseCacheLayer<St> {
+    store: St,
+    vary: Arc<[HeaderName]>,
+}
+impl<St> ResponseCacheLayer<St> {
+    pub fn new(store: St) -> Self {
+        Self {
+            store,
+            vary: Arc::from([] as [HeaderName; 0]),
+        }
+    }
+    /// Include the given request headers in the cache key.
+    pub fn vary(mut self, headers: impl IntoIterator<Item = HeaderName>) -> Self {
+        self.vary = headers.into_iter().collect();
+        self
+    }
+}
+impl<S, St> Layer<S> for ResponseCacheLayer<St>
+where
+    S: Clone + Send + Sync + 'static,
+    St: CacheStore,
+{
+    type Service = ResponseCacheService<S, St>;
+    fn layer(&self, inner: S) -> Self::Service {
+        ResponseCacheService {
+            inner,
+            store: self.store.clone(),
+            vary: self.vary.clone(),
+        }
+    }
+}
+
+/// # Response-caching [Service].
+///
+/// Stores the full response — status, headers and buffered body — keyed by the
+/// request method, URI and selected `Vary` headers, and serves hits without
+/// calling the inner service. The entry's *TTL* is taken from the `s-maxage`
+/// directive on the response, falling back to `max-age`, so wrapping a
+/// [CacheControlService](crate::CacheControlService) (applying this layer
+/// outside a [CacheControlLayer](crate::CacheControlLayer)) persists exactly
+/// what that layer declared cacheable. Served hits carry an `Age` header reflecting how
+/// long the entry has sat in the store. Non-cacheable responses stream through
+/// untouched — only responses that will be stored are buffered.
+#[derive(Clone, Debug)]
+pub struct ResponseCacheService<S, St>
+where
+    S: Clone + Send + Sync + 'static,
+    St: CacheStore,
+{
+    inner: S,
+    store: St,
+    vary: Arc<[HeaderName]>,
+}
+impl<ReqB, ResB, S, St> Service<Request<ReqB>> for ResponseCacheService<S, St>
+where
+    S: Service<Request<ReqB>, Response = Response<ResB>> + Clone + Send + Sync + 'static,
+    S::Future: Send + 'static,
+    ReqB: Send + 'static,
+    ResB: Body + Send + 'static,
+    ResB::Data: Send,
+    ResB::Error: Send + Sync + 'static,
+    St: CacheStore,
+{
+    type Response = Response<BoxBody<Bytes, ResB::Error>>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+    fn poll_ready(&mut self, cx: &mut task::Context<'_>) -> task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+    fn call(&mut self, req: Request<ReqB>) -> Self::Future {
+        // Only safe, idempotent methods are cacheable; a cached POST/PUT/DELETE
+        // would drop the inner call and silently replay a prior response.
+        let cacheable = matches!(*req.method(), Method::GET | Method::HEAD);
+        let key = Key::from_request(&req, &self.vary);
+        let store = self.store.clone();
+        let clone = self.inner.clone();
+        let mut inner = mem::replace(&mut self.inner, clone);
+        Box::pin(async move {
+            if cacheable {
+                if let Some(stored) = store.get(&key) {
+                    return Ok(stored.into_response());
+                }
+            }
+            let response = inner.call(req).await?;
+            // Decide cacheability from the headers before touching the body, so
+            // non-cacheable responses stream through untouched instead of being
+            // drained into memory for nothing.
+            let ttl = cacheable
+                .then(|| response.headers().typed_get::<CacheControl>())
+                .flatten()
+                .and_then(cacheable_ttl);
+            let Some(ttl) = ttl else {
+                return Ok(response.map(pass_through));
+            };
+            let (parts, body) = response.into_parts();
+            match body.collect().await {
+                Ok(collected) => {
+                    let body = collected.to_bytes();
+                    store.put(
+                        key,
+                        StoredResponse {
+                            status: parts.status,
+                            headers: parts.headers.clone(),
+                            body: body.clone(),
+                            stored_at: now_nanos(),
+                        },
+                        ttl,
+                    );
+                    Ok(Response::from_parts(parts, buffered(body)))
+                }
+                // A transient body error must not be cached as a truncated 200;
+                // propagate it downstream instead of poisoning the store.
+                Err(error) => Ok(Response::from_parts(parts, errored(error))),
+            }
+        })
+    }
+}
+
+/// The *TTL* to persist, unless the directives forbid caching.
+///
+/// Prefers `s-maxage` — this is a shared cache — and falls back to `max-age`.
+fn cacheable_ttl(header: CacheControl) -> Option<Duration> {
+    if header.no_store() || header.no_cache() || header.private() {
+        return None;
+    }
+    header.s_max_age().or_else(|| header.max_age())
+}
+
+/// Stream a body through unchanged, only re-typing its data frames to [Bytes].
+fn pass_through<B>(body: B) -> BoxBody<Bytes, B::Error>
+where
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: 'static,
+{
+    body.map_frame(|frame| frame.map_data(|mut data| data.copy_to_bytes(data.remaining())))
+        .boxed()
+}
+
+/// Wrap already-buffered bytes as a boxed body with an arbitrary error type.
+fn buffered<E>(bytes: Bytes) -> BoxBody<Bytes, E>
+where
+    E: 'static,
+{
+    Full::new(bytes).map_err(|never| match never {}).boxed()
+}
+
+/// A body that fails on first poll, forwarding an upstream collection error.
+fn errored<E>(error: E) -> BoxBody<Bytes, E>
+where
+    E: Send + Sync + 'static,
+{
+    Errored(Some(error)).boxed()
+}
+struct Errored<E>(Option<E>);
+impl<E> Body for Errored<E> {
+    type Data = Bytes;
+    type Error = E;
+    fn poll_frame(
+        self: std::pin::Pin<&mut Self>,
+        _cx: &mut task::Context<'_>,
+    ) -> task::Poll<Option<Result<http_body::Frame<Bytes>, E>>> {
+        // No field is pinned, so projecting to `&mut` is sound.
+        let this = unsafe { self.get_unchecked_mut() };
+        task::Poll::Ready(this.0.take().map(Err))
+    }
+}
+
+fn now_nanos() -> u128 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+}
+
+fn encode(key: &Key, response: &StoredResponse, buf: &mut Vec<u8>) {
+    let key = key.0.as_bytes();
+    buf.extend_from_slice(&(key.len() as u32).to_le_bytes());
+    buf.extend_from_slice(key);
+    buf.extend_from_slice(&response.stored_at.to_le_bytes());
+    buf.extend_from_slice(&response.status.as_u16().to_le_bytes());
+    buf.extend_from_slice(&(response.headers.len() as u32).to_le_bytes());
+    for (name, value) in &response.headers {
+        let name = name.as_str().as_bytes();
+        buf.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        buf.extend_from_slice(name);
+        buf.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        buf.extend_from_slice(value.as_bytes());
+    }
+    buf.extend_from_slice(&(response.body.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&response.body);
+}
+
+fn decode(mut bytes: &[u8]) -> Option<(String, StoredResponse)> {
+    let key = String::from_utf8(take_slice(&mut bytes)?.to_vec()).ok()?;
+    let stored_at = u128::from_le_bytes(take::<16>(&mut bytes)?);
+    let status = StatusCode::from_u16(u16::from_le_bytes(take::<2>(&mut bytes)?)).ok()?;
+    let count = u32::from_le_bytes(take::<4>(&mut bytes)?);
+    let mut headers = HeaderMap::new();
+    for _ in 0..count {
+        let name = take_slice(&mut bytes)?;
+        let value = take_slice(&mut bytes)?;
+        let name = HeaderName::from_bytes(name).ok()?;
+        let value = http::HeaderValue::from_bytes(value).ok()?;
+        headers.append(name, value);
+    }
+    let body = Bytes::copy_from_slice(take_slice(&mut bytes)?);
+    Some((
+        key,
+        StoredResponse {
+            status,
+            headers,
+            body,
+            stored_at,
+        },
+    ))
+}
+
+fn take<const N: usize>(bytes: &mut &[u8]) -> Option<[u8; N]> {
+    let (head, rest) = bytes.split_first_chunk::<N>()?;
+    *bytes = rest;
+    Some(*head)
+}
+
+fn take_slice<'a>(bytes: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len = u32::from_le_bytes(take::<4>(bytes)?) as usize;
+    if bytes.len() < len {
+        return None;
+    }
+    let (head, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Some(head)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(uri: &str) -> Key {
+        Key::from_request(&Request::get(uri).body(()).unwrap(), &[])
+    }
+
+    fn stored(body: &str) -> StoredResponse {
+        StoredResponse {
+            status: StatusCode::OK,
+            headers: HeaderMap::new(),
+            body: Bytes::from(body.to_owned()),
+            stored_at: now_nanos(),
+        }
+    }
+
+    #[test]
+    fn serves_a_fresh_hit() {
+        let store = HashMapStore::new();
+        store.put(key("/a"), stored("hit"), Duration::from_secs(60));
+        assert_eq!(store.get(&key("/a")).unwrap().body, Bytes::from("hit"));
+    }
+
+    #[test]
+    fn expired_entry_is_a_miss() {
+        let store = HashMapStore::new();
+        store.put(key("/a"), stored("stale"), Duration::from_secs(0));
+        assert!(store.get(&key("/a")).is_none());
+    }
+
+    #[test]
+    fn disk_round_trip() {
+        let mut buf = Vec::new();
+        let mut response = stored("on disk");
+        response
+            .headers
+            .insert("x-test", http::HeaderValue::from_static("1"));
+        encode(&key("/a"), &response, &mut buf);
+        let (k, decoded) = decode(&buf).unwrap();
+        assert_eq!(k, key("/a").0);
+        assert_eq!(decoded.status, StatusCode::OK);
+        assert_eq!(decoded.headers.get("x-test").unwrap(), "1");
+        assert_eq!(decoded.body, Bytes::from("on disk"));
+        assert_eq!(decoded.stored_at, response.stored_at);
+    }
+}
@@ -1,8 +1,14 @@
 #![doc = include_str!("../README.md")]
 
+mod cache;
+
 use std::{mem, task, time::Duration};
 
 use futures::future::BoxFuture;
+pub use cache::{
+    CacheStore, DiskStore, HashMapStore, Key, MokaStore, ResponseCacheLayer, ResponseCacheService,
+    StoredResponse,
+};
 pub use headers::CacheControl;
 use headers::HeaderMapExt;
 use http::{Request, Response, StatusCode};